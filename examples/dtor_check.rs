@@ -0,0 +1,57 @@
+//! Exercised by `tests/constructors.rs`, which runs this example as a
+//! subprocess (optionally under `--release` LTO or `+crt-static`) and checks
+//! it exits successfully and prints `DTOR_RAN`. That's the only way to
+//! observe that a `#[destructor]` hook actually fired: it runs after `main`
+//! returns, in the same process, so nothing inside this binary can assert on
+//! it — a later process has to check the output instead.
+extern crate contructor_derive;
+extern crate libc;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use contructor_derive::{constructor, constructor_block, destructor};
+
+static FN_CTOR_RAN: AtomicBool = AtomicBool::new(false);
+
+#[constructor]
+extern "C" fn fn_ctor() {
+    FN_CTOR_RAN.store(true, Ordering::SeqCst);
+}
+
+static BLOCK_CTOR_RAN: AtomicBool = AtomicBool::new(false);
+
+constructor_block! {
+    BLOCK_CTOR_RAN.store(true, Ordering::SeqCst);
+}
+
+#[constructor]
+static GREETING: String = String::from("hello from a load-time static");
+
+static ORDER: AtomicUsize = AtomicUsize::new(0);
+static FIRST_SLOT: AtomicUsize = AtomicUsize::new(0);
+static SECOND_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+#[constructor(200)]
+extern "C" fn runs_second() {
+    SECOND_SLOT.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+}
+
+#[constructor(100)]
+extern "C" fn runs_first() {
+    FIRST_SLOT.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+}
+
+#[destructor]
+extern "C" fn on_exit() {
+    println!("DTOR_RAN");
+}
+
+fn main() {
+    assert!(FN_CTOR_RAN.load(Ordering::SeqCst), "#[constructor] fn did not run before main");
+    assert!(BLOCK_CTOR_RAN.load(Ordering::SeqCst), "{}", "constructor_block!{{}} did not run before main");
+    assert_eq!(&*GREETING, "hello from a load-time static");
+    assert!(
+        FIRST_SLOT.load(Ordering::SeqCst) < SECOND_SLOT.load(Ordering::SeqCst),
+        "#[constructor(100)] must run before #[constructor(200)]"
+    );
+}
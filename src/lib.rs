@@ -15,8 +15,13 @@
 //! ```toml
 //! [dependencies]
 //! contructor_derive = "0.1"
+//! libc = "0.2"
 //! ```
 //!
+//! `libc` is required because `#[destructor]` registers its teardown hook
+//! with `atexit`/`__cxa_atexit` rather than relying on a (now unreliable)
+//! linker section.
+//!
 //! Example
 //! =======
 //!
@@ -47,21 +52,36 @@ extern crate proc_macro2;
 extern crate quote;
 extern crate syn;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
-use syn::{Expr, Item, ItemFn, Lit};
+use syn::parse::{Parse, ParseStream};
+use syn::{Expr, Item, ItemFn, ItemStatic, Lit, LitInt, Token};
+
+/// Disambiguates the hidden functions `constructor_block!` generates, so
+/// multiple invocations in the same module don't collide on the same name.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
-/// Registers a function to be called before main (if an executable) or when loaded (if a dynamic library).
+/// Builds an identifier derived from `prefix` that is unique for the
+/// lifetime of this proc-macro process.
+fn unique_ident(prefix: &Ident) -> Ident {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+
+    Ident::new(&format!("__{}_{}", prefix, id), Span::call_site())
+}
+
+/// Registers a function to be called before main (if an executable) or when loaded (if a dynamic library),
+/// or initializes a `static` at load time instead of at its first access.
 #[proc_macro_attribute]
 pub fn constructor(args: TokenStream, input: TokenStream) -> TokenStream {
     let item: Item = syn::parse(input).unwrap();
+    let priority = parse_priority(args);
 
-    if let Item::Fn(ref func) = item {
-        let priority = parse_priority(args);
-
-        gen_ctor(func, priority).into()
-    } else {
-        panic!("constructor!{} is only defined for function!");
+    match item {
+        Item::Fn(ref func) => gen_ctor(func, priority).into(),
+        Item::Static(ref item) => gen_static_ctor(item, priority).into(),
+        _ => panic!("{}", "constructor!{} is only defined for function or static!"),
     }
 }
 
@@ -75,17 +95,64 @@ pub fn destructor(args: TokenStream, input: TokenStream) -> TokenStream {
 
         gen_dtor(func, priority).into()
     } else {
-        panic!("destructor!{} is only defined for function!");
+        panic!("{}", "destructor!{} is only defined for function!");
     }
 }
 
+struct ConstructorBlock {
+    priority: Option<u64>,
+    body: TokenStream2,
+}
+
+impl Parse for ConstructorBlock {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let priority = if input.peek(LitInt) && input.peek2(Token![,]) {
+            let lit: LitInt = input.parse()?;
+            input.parse::<Token![,]>()?;
+
+            Some(lit.base10_parse().unwrap())
+        } else {
+            None
+        };
+
+        Ok(ConstructorBlock {
+            priority,
+            body: input.parse()?,
+        })
+    }
+}
+
+/// `constructor_block! { [priority,] stmt; .. }` mirrors the `startup` crate's
+/// `on_startup! { .. }`: it wraps an anonymous block in a hidden `extern "C"`
+/// function and registers it exactly like `#[constructor]`, so users don't
+/// have to declare and name a function just to run some code before `main`.
+///
+/// Named `constructor_block!` rather than `constructor!`: a proc-macro crate
+/// can't export a `#[proc_macro_attribute]` and a `#[proc_macro]` under the
+/// same Rust function name, since they'd collide in the value namespace
+/// before macro expansion ever happens. This is the settled name for that
+/// reason, not a placeholder.
+#[proc_macro]
+pub fn constructor_block(input: TokenStream) -> TokenStream {
+    let ConstructorBlock { priority, body } = syn::parse(input).unwrap();
+
+    let func_name = unique_ident(&Ident::new("constructor_block", Span::call_site()));
+    let func: ItemFn = syn::parse2(quote! {
+        extern "C" fn #func_name() {
+            #body
+        }
+    }).unwrap();
+
+    gen_ctor(&func, priority).into()
+}
+
 fn parse_priority(args: TokenStream) -> Option<u64> {
     if !args.is_empty() {
         let expr: Expr = syn::parse(args).unwrap();
 
         if let Expr::Lit(lit) = expr {
             if let Lit::Int(n) = lit.lit {
-                return Some(n.value());
+                return Some(n.base10_parse().unwrap());
             }
         }
     }
@@ -93,31 +160,87 @@ fn parse_priority(args: TokenStream) -> Option<u64> {
     None
 }
 
-fn gen_ctor(func: &ItemFn, _priority: Option<u64>) -> TokenStream2 {
-    let mod_name = Ident::new(&format!("{}_ctor", func.ident), Span::call_site());
-    let func_name = &func.ident;
+/// GNU ld/lld sort numbered `.init_array.N` sections as plain text, so this
+/// is the highest priority that still sorts correctly against every other
+/// zero-padded 5-digit value. GCC's own `constructor(priority)` attribute
+/// caps out lower, at 65535 (0-100 are reserved for the implementation) —
+/// we don't need to match that limit since we aren't sharing GCC's ordering
+/// scheme, just borrowing the same idea of a numeric priority.
+const MAX_PRIORITY: u64 = 99_999;
 
-    let ctor = if cfg!(target_os = "linux") {
-        quote! {
-            #[link_section = ".ctors"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+/// Emits the `#[link_section]` static named `func_name` that places `target`
+/// (an expression evaluating to an `extern fn()`) into the platform's
+/// load-time constructor section, honoring `priority` where the platform
+/// supports ordering. `func_name` only has to be unique within its enclosing
+/// module — the static is never `#[no_mangle]`, so normal Rust name
+/// mangling already keeps it from colliding with anything in another module
+/// or crate. The static is marked `#[used]` so `-C link-dead-code=no`, LTO
+/// or `+crt-static` can't drop it as dead code before the linker ever sees
+/// the section.
+fn ctor_section(func_name: &Ident, target: &TokenStream2, priority: Option<u64>) -> TokenStream2 {
+    if cfg!(any(target_os = "macos", target_os = "ios")) {
+        if priority.is_some() {
+            return quote! {
+                compile_error!("priority is not supported for #[constructor] on macOS/iOS");
+            };
         }
-    } else if cfg!(target_os = "macos") {
+
         quote! {
+            #[used]
+            #[allow(non_upper_case_globals)]
             #[link_section = "__DATA,__mod_init_func"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+            static #func_name: extern fn() = #target;
         }
     } else if cfg!(target_os = "windows") {
         quote! {
+            #[used]
+            #[allow(non_upper_case_globals)]
             #[link_section = ".CRT$XCU"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+            static #func_name: extern fn() = #target;
+        }
+    } else if cfg!(target_family = "unix") {
+        // Linux and the other ELF unixes (the BSDs, Android, illumos, ...)
+        // all honor `.init_array`. GNU ld/lld sort numbered `.init_array.N`
+        // sections lexically and run `.init_array` in ascending order, so a
+        // lower priority runs earlier; unnumbered entries run last. We
+        // haven't verified every one of these platforms firsthand, but they
+        // all implement the same ELF startup convention, so we assume it
+        // holds the same way the `startup` crate does.
+        if let Some(n) = priority {
+            if n > MAX_PRIORITY {
+                let msg = format!(
+                    "priority {} exceeds the maximum of {}: higher values don't sort \
+                     correctly against the zero-padded 5-digit init_array section name",
+                    n, MAX_PRIORITY
+                );
+
+                return quote! { compile_error!(#msg); };
+            }
+        }
+
+        let section = match priority {
+            Some(n) => format!(".init_array.{:05}", n),
+            None => ".init_array".to_owned(),
+        };
+
+        quote! {
+            #[used]
+            #[allow(non_upper_case_globals)]
+            #[link_section = #section]
+            static #func_name: extern fn() = #target;
         }
     } else {
-        unimplemented!()
-    };
+        quote! {
+            compile_error!("#[constructor]/#[destructor] is not supported on this target");
+        }
+    }
+}
+
+fn gen_ctor(func: &ItemFn, priority: Option<u64>) -> TokenStream2 {
+    let mod_name = Ident::new(&format!("{}_ctor", func.sig.ident), Span::call_site());
+    let func_name = &func.sig.ident;
+    let target = quote!(super::#func_name);
+    let ctor = ctor_section(func_name, &target, priority);
 
     quote!{
         #func
@@ -129,36 +252,136 @@ fn gen_ctor(func: &ItemFn, _priority: Option<u64>) -> TokenStream2 {
     }
 }
 
-fn gen_dtor(func: &ItemFn, _priority: Option<u64>) -> TokenStream2 {
-    let mod_name = Ident::new(&format!("{}_dtor", func.ident), Span::call_site());
-    let func_name = &func.ident;
-    let ctor = if cfg!(target_os = "linux") {
-        quote! {
-            #[link_section = ".dtors"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+/// `#[constructor]` on a `static FOO: T = expr;` defers evaluation of `expr`
+/// to load time instead of requiring it to be `const`. `FOO` itself becomes a
+/// zero-sized unit struct that `Deref`s into the now-initialized backing
+/// storage. The storage is a `MaybeUninit<T>` read with `assume_init_ref`, so
+/// there's no discriminant check or panic path on access, unlike
+/// `lazy_static`'s per-access initialization check — reads cost exactly what
+/// a plain `static` costs. The flip side of skipping that check: `Deref`
+/// trusts the ctor already ran and hands out `&T` with no synchronization,
+/// so reading `FOO` before load time, or racing the loader from another
+/// thread, is undefined behavior rather than a panic.
+fn gen_static_ctor(item: &ItemStatic, priority: Option<u64>) -> TokenStream2 {
+    let vis = &item.vis;
+    let name = &item.ident;
+    let ty = &item.ty;
+    let expr = &item.expr;
+
+    let mod_name = Ident::new(&format!("{}_ctor", name), Span::call_site());
+    let storage_name = Ident::new(&format!("__{}_STORAGE", name), Span::call_site());
+    let init_name = Ident::new(&format!("__{}_init", name), Span::call_site());
+
+    let target = quote!(super::#init_name);
+    let ctor = ctor_section(&init_name, &target, priority);
+
+    quote! {
+        #[allow(non_camel_case_types)]
+        #vis struct #name;
+
+        impl ::std::ops::Deref for #name {
+            type Target = #ty;
+
+            fn deref(&self) -> &#ty {
+                // Sound as long as the ctor below has already run, which it
+                // has by the time any other code in this binary can observe
+                // `#name` (load-time ctors run before `main`/`dlopen`
+                // returns). There is no happens-before check here: reading
+                // `#name` from code that itself runs at load time, before
+                // this ctor, is undefined behavior.
+                unsafe { #storage_name.assume_init_ref() }
+            }
         }
-    } else if cfg!(target_os = "macos") {
-        quote! {
-            #[link_section = "__DATA,__mod_term_func"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+
+        static mut #storage_name: ::std::mem::MaybeUninit<#ty> = ::std::mem::MaybeUninit::uninit();
+
+        #[doc(hidden)]
+        extern "C" fn #init_name() {
+            unsafe {
+                #storage_name = ::std::mem::MaybeUninit::new(#expr);
+            }
         }
-    } else if cfg!(target_os = "windows") {
+
+        #[doc(hidden)]
+        pub mod #mod_name {
+            #ctor
+        }
+    }
+}
+
+/// `#[destructor]` no longer places `func` directly into a teardown section:
+/// those sections (`.dtors`, `__mod_term_func`, `.CRT$XPU`) are either
+/// removed (modern macOS) or unreliable (mingw). Instead it registers `func`
+/// at load time via `atexit`/`__cxa_atexit`, the same technique the `ctor`
+/// crate uses, so teardown also fires on `dlclose` for `cdylib` outputs and
+/// runs in the LIFO order those APIs guarantee.
+///
+/// `__cxa_atexit` is an Itanium C++ ABI entry point, not a POSIX one, so the
+/// `libc` crate only exposes it for the handful of targets it already needs
+/// it for internally (e.g. QNX) — it's absent for ordinary Linux/BSD/macOS
+/// targets even though their C runtimes all provide it. We declare it
+/// ourselves instead of going through `::libc::__cxa_atexit`.
+fn gen_dtor(func: &ItemFn, priority: Option<u64>) -> TokenStream2 {
+    if priority.is_some() {
+        // `ctor_section` would place the *registration* thunk (not `func`
+        // itself) into the numbered section, so a lower priority registers
+        // earlier via `atexit`/`__cxa_atexit` — and since those run LIFO at
+        // actual teardown, it would fire *later*, inverted from what
+        // `#[constructor(priority)]` trains callers to expect. Rather than
+        // ship that silently, reject it until there's a real ordering
+        // scheme for destructors.
+        return quote! {
+            #func
+
+            compile_error!("priority is not supported for #[destructor]");
+        };
+    }
+
+    let mod_name = Ident::new(&format!("{}_dtor", func.sig.ident), Span::call_site());
+    let func_name = &func.sig.ident;
+    let register_name = Ident::new(&format!("{}_dtor_register", func.sig.ident), Span::call_site());
+    let static_name = Ident::new(&format!("{}_dtor_ctor", func.sig.ident), Span::call_site());
+
+    let register = if cfg!(target_os = "windows") {
         quote! {
-            #[link_section = ".CRT$XPU"]
-            #[no_mangle]
-            pub static #func_name: extern fn() = super::#func_name;
+            extern "C" fn #register_name() {
+                unsafe { ::libc::atexit(super::#func_name); }
+            }
         }
     } else {
-        unimplemented!()
+        quote! {
+            extern "C" {
+                fn __cxa_atexit(
+                    func: extern "C" fn(*mut ::libc::c_void),
+                    arg: *mut ::libc::c_void,
+                    dso_handle: *mut ::libc::c_void,
+                ) -> ::libc::c_int;
+
+                static __dso_handle: u8;
+            }
+
+            extern "C" fn #register_name() {
+                unsafe {
+                    __cxa_atexit(
+                        ::std::mem::transmute(super::#func_name as extern "C" fn()),
+                        ::std::ptr::null_mut(),
+                        &__dso_handle as *const u8 as *mut ::libc::c_void,
+                    );
+                }
+            }
+        }
     };
 
+    let target = quote!(#register_name);
+    let ctor = ctor_section(&static_name, &target, priority);
+
     quote!{
         #func
 
         #[doc(hidden)]
         pub mod #mod_name {
+            #register
+
             #ctor
         }
     }
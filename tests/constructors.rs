@@ -0,0 +1,81 @@
+//! Runs `examples/dtor_check` as a subprocess under a few different build
+//! configurations and checks that `#[constructor]`/`#[destructor]` still
+//! fire. That's the regression this locks in: the generated section statics
+//! have no Rust-side references, so LTO or `+crt-static` could in principle
+//! let the linker garbage-collect them and silently drop the hooks (see
+//! `ctor_section`'s `#[used]`).
+use std::env;
+use std::process::Command;
+
+fn host_triple() -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let output = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .expect("failed to run `rustc -vV`");
+    let info = String::from_utf8_lossy(&output.stdout);
+
+    info.lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .expect("`rustc -vV` did not report a host triple")
+        .to_owned()
+}
+
+fn run_example(extra_args: &[&str], extra_rustflags: Option<&str>) -> (bool, String) {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let mut cmd = Command::new(cargo);
+    cmd.args(["run", "--quiet", "--example", "dtor_check"]);
+    cmd.args(extra_args);
+
+    if let Some(flags) = extra_rustflags {
+        cmd.env("RUSTFLAGS", flags);
+    }
+
+    let output = cmd.output().expect("failed to run `cargo run --example dtor_check`");
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    (output.status.success(), format!("stdout:\n{}\nstderr:\n{}", stdout, stderr))
+}
+
+#[test]
+fn hooks_survive_default_build() {
+    let (success, output) = run_example(&[], None);
+
+    assert!(success, "{}", output);
+    assert!(output.contains("DTOR_RAN"), "#[destructor] did not run:\n{}", output);
+}
+
+#[test]
+fn hooks_survive_lto() {
+    // `--release` rather than `RUSTFLAGS="-C lto"`: the proc-macro crate
+    // itself gets rebuilt with the same flags, and rustc rejects `-C lto`
+    // combined with the `-C embed-bitcode=no` cargo passes for rlib/dylib
+    // outputs like a proc-macro crate. `[profile.release] lto = true` only
+    // applies `-C lto` to the final binary's own codegen units.
+    let (success, output) = run_example(&["--release"], None);
+
+    assert!(success, "{}", output);
+    assert!(output.contains("DTOR_RAN"), "#[destructor] did not survive LTO:\n{}", output);
+}
+
+#[test]
+fn hooks_survive_crt_static() {
+    // `--target` (even the host triple) makes cargo build host tools — the
+    // proc-macro crate included — without `RUSTFLAGS`, so only the example
+    // binary itself picks up `+crt-static`. A proc-macro can't be built for
+    // a `+crt-static` target, so without `--target` this flag would also
+    // apply to the proc-macro's own compilation and fail the build outright.
+    let host = host_triple();
+    let (success, output) = run_example(
+        &["--target", &host],
+        Some("-C target-feature=+crt-static"),
+    );
+
+    assert!(success, "{}", output);
+    assert!(
+        output.contains("DTOR_RAN"),
+        "#[destructor] did not survive +crt-static:\n{}",
+        output
+    );
+}